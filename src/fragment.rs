@@ -0,0 +1,212 @@
+//! IPv4 fragment reassembly, so a datagram split across multiple frames is
+//! made whole again before its payload is handed to TCP parsing.
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// How long an incomplete set of fragments is kept before being evicted,
+/// bounding the memory a never-completing datagram can hold onto.
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct FragmentKey {
+    src: Ipv4Addr,
+    dst: Ipv4Addr,
+    protocol: u8,
+    identification: u16,
+}
+
+struct FragmentEntry {
+    /// Sparse reassembly buffer, indexed by byte offset into the original
+    /// (unfragmented) payload.
+    buf: Vec<u8>,
+    /// Byte ranges of `buf` that have been filled in so far, kept sorted
+    /// and merged so adjacent/overlapping fragments collapse into one
+    /// interval.
+    received: Vec<Range<usize>>,
+    /// Total payload length, known once the final fragment (`MF = 0`) has
+    /// arrived.
+    total_len: Option<usize>,
+    last_seen: Instant,
+}
+
+impl FragmentEntry {
+    fn new() -> Self {
+        FragmentEntry {
+            buf: Vec::new(),
+            received: Vec::new(),
+            total_len: None,
+            last_seen: Instant::now(),
+        }
+    }
+
+    fn insert(&mut self, offset: usize, data: &[u8], more_fragments: bool) {
+        let end = offset + data.len();
+        if self.buf.len() < end {
+            self.buf.resize(end, 0);
+        }
+        self.buf[offset..end].copy_from_slice(data);
+        if !more_fragments {
+            self.total_len = Some(end);
+        }
+        self.last_seen = Instant::now();
+        self.merge_interval(offset..end);
+    }
+
+    /// Inserts `new` into `received`, merging it with any interval it
+    /// overlaps or touches so the set stays in its canonical, non-adjacent
+    /// form.
+    fn merge_interval(&mut self, new: Range<usize>) {
+        self.received.push(new);
+        self.received.sort_by_key(|r| r.start);
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(self.received.len());
+        for r in self.received.drain(..) {
+            match merged.last_mut() {
+                Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+                _ => merged.push(r),
+            }
+        }
+        self.received = merged;
+    }
+
+    /// True once the final fragment has arrived and the received intervals
+    /// cover the whole datagram with no gaps.
+    fn is_complete(&self) -> bool {
+        match self.total_len {
+            Some(total) => matches!(self.received.as_slice(), [r] if *r == (0..total)),
+            None => false,
+        }
+    }
+}
+
+/// Reassembles IPv4 fragments, keyed by the four-tuple RFC 791 uses to
+/// identify a datagram's fragments: source, destination, protocol, and
+/// identification field.
+pub struct FragmentCache {
+    entries: HashMap<FragmentKey, FragmentEntry>,
+}
+
+impl FragmentCache {
+    pub fn new() -> Self {
+        FragmentCache {
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Feeds one fragment's payload into the cache. Returns the
+    /// reassembled datagram payload once every fragment has arrived;
+    /// otherwise `None`, with the fragment held for a later call to
+    /// complete.
+    #[allow(clippy::too_many_arguments)]
+    pub fn insert(
+        &mut self,
+        src: Ipv4Addr,
+        dst: Ipv4Addr,
+        protocol: u8,
+        identification: u16,
+        fragment_offset: u16,
+        more_fragments: bool,
+        payload: &[u8],
+    ) -> Option<Vec<u8>> {
+        self.evict_expired();
+        let key = FragmentKey {
+            src,
+            dst,
+            protocol,
+            identification,
+        };
+        let entry = self.entries.entry(key).or_insert_with(FragmentEntry::new);
+        entry.insert(fragment_offset as usize * 8, payload, more_fragments);
+        if entry.is_complete() {
+            self.entries.remove(&key).map(|entry| entry.buf)
+        } else {
+            None
+        }
+    }
+
+    fn evict_expired(&mut self) {
+        let now = Instant::now();
+        self.entries
+            .retain(|_, entry| now.saturating_duration_since(entry.last_seen) < FRAGMENT_TIMEOUT);
+    }
+}
+
+impl Default for FragmentCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_is_incomplete_without_a_final_fragment() {
+        let mut entry = FragmentEntry::new();
+        entry.insert(0, &[1, 2, 3, 4], true);
+        assert!(!entry.is_complete());
+    }
+
+    #[test]
+    fn entry_is_incomplete_with_a_gap_between_fragments() {
+        let mut entry = FragmentEntry::new();
+        entry.insert(0, &[1, 2, 3, 4], true);
+        entry.insert(8, &[9, 10], false);
+        assert!(!entry.is_complete());
+    }
+
+    #[test]
+    fn entry_completes_once_gap_is_filled() {
+        let mut entry = FragmentEntry::new();
+        entry.insert(0, &[1, 2, 3, 4], true);
+        entry.insert(8, &[9, 10], false);
+        entry.insert(4, &[5, 6, 7, 8], true);
+        assert!(entry.is_complete());
+        assert_eq!(entry.buf, vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn entry_merges_overlapping_fragments() {
+        let mut entry = FragmentEntry::new();
+        entry.insert(0, &[1, 2, 3, 4], true);
+        // Re-delivered fragment overlapping the first one by two bytes.
+        entry.insert(2, &[3, 4, 5, 6], true);
+        entry.insert(6, &[7, 8], false);
+        assert!(entry.is_complete());
+        assert_eq!(entry.buf, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+    }
+
+    #[test]
+    fn cache_reassembles_out_of_order_fragments() {
+        let mut cache = FragmentCache::new();
+        let src = Ipv4Addr::new(10, 0, 0, 1);
+        let dst = Ipv4Addr::new(10, 0, 0, 2);
+        // Second fragment arrives first; offset field is in units of 8 bytes.
+        assert_eq!(cache.insert(src, dst, 6, 1, 1, false, &[9, 10, 11, 12]), None);
+        assert_eq!(
+            cache.insert(src, dst, 6, 1, 0, true, &[1, 2, 3, 4, 5, 6, 7, 8]),
+            Some(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12])
+        );
+    }
+
+    #[test]
+    fn cache_keeps_different_datagrams_separate() {
+        let mut cache = FragmentCache::new();
+        let src = Ipv4Addr::new(10, 0, 0, 1);
+        let dst = Ipv4Addr::new(10, 0, 0, 2);
+        // Same source/destination/protocol, different identification field:
+        // the final fragment of datagram 2 must not complete datagram 1.
+        assert_eq!(
+            cache.insert(src, dst, 6, 1, 0, true, &[1, 2, 3, 4, 5, 6, 7, 8]),
+            None
+        );
+        assert_eq!(cache.insert(src, dst, 6, 2, 1, false, &[9, 10]), None);
+        assert_eq!(
+            cache.insert(src, dst, 6, 1, 1, false, &[11, 12]),
+            Some(vec![1, 2, 3, 4, 5, 6, 7, 8, 11, 12])
+        );
+    }
+}
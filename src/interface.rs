@@ -0,0 +1,374 @@
+//! A blocking, pollable socket API layered on top of [`tcp::Connection`],
+//! so callers don't have to drive the state machine themselves by handing
+//! raw packets to callbacks. [`Interface`] owns the tun device and the
+//! background threads; [`TcpListener`] and [`TcpStream`] are cheap handles
+//! into its shared state.
+
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::fragment;
+use crate::tcp::{self, IpAddress, IpMeta};
+
+/// A TCP connection's four-tuple, abstracting over IPv4/IPv6 the same way
+/// `tcp::IpMeta` does.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Quad {
+    src: (IpAddress, u16),
+    dst: (IpAddress, u16),
+}
+
+/// How often the poll loop drives `Connection::on_tick` when no packet has
+/// arrived in the meantime.
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+struct ConnectionsState {
+    connections: HashMap<Quad, tcp::Connection>,
+    /// Per-port queue of quads that finished their handshake and are
+    /// waiting to be handed out by `TcpListener::accept`.
+    pending: HashMap<u16, VecDeque<Quad>>,
+}
+
+struct SharedState {
+    state: Mutex<ConnectionsState>,
+    /// Signalled whenever `state` changes, so blocked `accept`/`read` calls
+    /// can wake up and recheck their condition.
+    cond: Condvar,
+}
+
+/// Owns the tun device and the reader/poll threads that drive every
+/// connection's state machine. The threads are detached: dropping it does
+/// not stop them.
+pub struct Interface {
+    shared: Arc<SharedState>,
+}
+
+impl Interface {
+    /// Brings up `name` as a tun interface and starts the background
+    /// threads, validating and computing every checksum. `bind` can be
+    /// called any number of times afterwards to listen on additional
+    /// ports.
+    pub fn new(name: &str) -> io::Result<Self> {
+        Self::with_checksums(name, tcp::ChecksumCapabilities::default())
+    }
+
+    /// Like [`Interface::new`], but with explicit control over which
+    /// checksums are verified/computed - useful when the tun device or an
+    /// underlying NIC already offloads that work.
+    pub fn with_checksums(name: &str, checksums: tcp::ChecksumCapabilities) -> io::Result<Self> {
+        let nic = tun_tap::Iface::without_packet_info(name, tun_tap::Mode::Tun)?;
+        let reader_nic = nic.try_clone()?;
+        let shared = Arc::new(SharedState {
+            state: Mutex::new(ConnectionsState {
+                connections: HashMap::new(),
+                pending: HashMap::new(),
+            }),
+            cond: Condvar::new(),
+        });
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let nic = reader_nic;
+            loop {
+                let mut buf = [0u8; 1500];
+                match nic.recv(&mut buf[..]) {
+                    Ok(read) => {
+                        if tx.send(buf[..read].to_vec()).is_err() {
+                            return;
+                        }
+                    }
+                    Err(err) => {
+                        eprintln!("failed to read from {}: err={err:?}", "tun device");
+                        return;
+                    }
+                }
+            }
+        });
+
+        let poll_shared = shared.clone();
+        thread::spawn(move || {
+            let mut nic = nic;
+            let iss_gen = tcp::IssGenerator::new();
+            let mut fragments = fragment::FragmentCache::new();
+            loop {
+                match rx.recv_timeout(TICK_INTERVAL) {
+                    Ok(buf) => {
+                        if let Err(err) = handle_packet(
+                            &mut nic,
+                            &poll_shared,
+                            &iss_gen,
+                            checksums,
+                            &mut fragments,
+                            &buf,
+                        ) {
+                            eprintln!("failed to handle packet: err={err:?}");
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => {
+                        let mut state = poll_shared.state.lock().unwrap();
+                        for conn in state.connections.values_mut() {
+                            if let Err(err) = conn.on_tick(&mut nic) {
+                                eprintln!("on_tick failed: err={err:?}");
+                            }
+                        }
+                        state.connections.retain(|_, conn| !conn.is_closed());
+                        poll_shared.cond.notify_all();
+                    }
+                    Err(RecvTimeoutError::Disconnected) => return,
+                }
+            }
+        });
+
+        Ok(Interface { shared })
+    }
+
+    /// Registers `port` as listening and returns a handle to accept
+    /// incoming connections on it.
+    pub fn bind(&self, port: u16) -> io::Result<TcpListener> {
+        let mut state = self.shared.state.lock().unwrap();
+        state.pending.entry(port).or_default();
+        Ok(TcpListener {
+            port,
+            shared: self.shared.clone(),
+        })
+    }
+}
+
+/// A bound port, waiting for peers to complete the handshake.
+pub struct TcpListener {
+    port: u16,
+    shared: Arc<SharedState>,
+}
+
+impl TcpListener {
+    /// Blocks until a connection on this port has finished its handshake,
+    /// then returns a stream for it.
+    pub fn accept(&self) -> io::Result<TcpStream> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(queue) = state.pending.get_mut(&self.port) {
+                if let Some(quad) = queue.pop_front() {
+                    return Ok(TcpStream {
+                        quad,
+                        shared: self.shared.clone(),
+                    });
+                }
+            }
+            state = self.shared.cond.wait(state).unwrap();
+        }
+    }
+}
+
+/// A single established connection. Cloning a handle is not supported -
+/// reads and writes go through the connection's own buffers, which aren't
+/// meant to be drained from two places at once.
+pub struct TcpStream {
+    quad: Quad,
+    shared: Arc<SharedState>,
+}
+
+impl TcpStream {
+    /// Blocks until at least one byte has arrived, then copies as much as
+    /// fits into `buf`. Returns `Ok(0)` once the peer has closed and
+    /// nothing more is buffered, mirroring `Read::read` at EOF.
+    pub fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            let conn = state
+                .connections
+                .get_mut(&self.quad)
+                .ok_or_else(connection_reset)?;
+            if conn.has_incoming() {
+                let data = conn.take_incoming();
+                let n = data.len().min(buf.len());
+                buf[..n].copy_from_slice(&data[..n]);
+                return Ok(n);
+            }
+            if conn.peer_closed() {
+                return Ok(0);
+            }
+            state = self.shared.cond.wait(state).unwrap();
+        }
+    }
+
+    /// Queues `buf` for transmission and returns immediately; the poll
+    /// thread flushes it on its next tick.
+    pub fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        let mut state = self.shared.state.lock().unwrap();
+        let conn = state
+            .connections
+            .get_mut(&self.quad)
+            .ok_or_else(connection_reset)?;
+        conn.queue_outgoing(buf);
+        self.shared.cond.notify_all();
+        Ok(buf.len())
+    }
+
+    /// Requests an active close. The FIN itself goes out on the poll
+    /// thread's next tick, once any queued writes have drained.
+    pub fn close(&self) -> io::Result<()> {
+        let mut state = self.shared.state.lock().unwrap();
+        let conn = state
+            .connections
+            .get_mut(&self.quad)
+            .ok_or_else(connection_reset)?;
+        conn.request_close();
+        self.shared.cond.notify_all();
+        Ok(())
+    }
+}
+
+fn connection_reset() -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionReset, "connection no longer tracked")
+}
+
+/// Parses an IPv4 datagram, validating its checksum and reassembling it
+/// via `fragments` if needed. Returns the reassembled TCP segment (header
+/// plus data), or `None` if the datagram isn't carrying TCP, failed
+/// validation, or is a fragment still waiting on the rest of its datagram.
+fn parse_ipv4(
+    buf: &[u8],
+    checksums: tcp::ChecksumCapabilities,
+    fragments: &mut fragment::FragmentCache,
+) -> Result<Option<(IpMeta, Vec<u8>)>, etherparse::ReadError> {
+    let ip_hdr = etherparse::Ipv4HeaderSlice::from_slice(buf)?;
+    if ip_hdr.protocol() != etherparse::IpNumber::Tcp as u8 {
+        return Ok(None);
+    }
+    if checksums.ipv4_rx == tcp::ChecksumAction::Validate
+        && ip_hdr.to_header().calc_header_checksum() != ip_hdr.header_checksum()
+    {
+        return Ok(None);
+    }
+    let ip = IpMeta {
+        src: IpAddress::V4(ip_hdr.source_addr()),
+        dst: IpAddress::V4(ip_hdr.destination_addr()),
+    };
+    let header_end = ip_hdr.slice().len();
+    let fragment_payload = &buf[header_end..];
+    let segment = if ip_hdr.more_fragments() || ip_hdr.fragments_offset() != 0 {
+        let reassembled = fragments.insert(
+            ip_hdr.source_addr(),
+            ip_hdr.destination_addr(),
+            ip_hdr.protocol(),
+            ip_hdr.identification(),
+            ip_hdr.fragments_offset(),
+            ip_hdr.more_fragments(),
+            fragment_payload,
+        );
+        match reassembled {
+            Some(segment) => segment,
+            None => return Ok(None),
+        }
+    } else {
+        fragment_payload.to_vec()
+    };
+    Ok(Some((ip, segment)))
+}
+
+/// Parses an IPv6 datagram, walking past any extension headers to find the
+/// TCP header, or `None` if the final next-header isn't TCP. IPv6 routers
+/// never fragment in flight, so no reassembly is needed here.
+fn parse_ipv6(buf: &[u8]) -> Result<Option<(IpMeta, &[u8])>, etherparse::ReadError> {
+    let ip_hdr = etherparse::Ipv6HeaderSlice::from_slice(buf)?;
+    let header_end = ip_hdr.slice().len();
+    let (extensions, next_header) =
+        etherparse::Ipv6ExtensionsSlice::from_slice(ip_hdr.next_header(), &buf[header_end..])?;
+    if next_header != etherparse::IpNumber::Tcp {
+        return Ok(None);
+    }
+    let ip = IpMeta {
+        src: IpAddress::V6(ip_hdr.source_addr()),
+        dst: IpAddress::V6(ip_hdr.destination_addr()),
+    };
+    let payload_start = header_end + extensions.slice().len();
+    Ok(Some((ip, &buf[payload_start..])))
+}
+
+fn handle_packet(
+    nic: &mut tun_tap::Iface,
+    shared: &Arc<SharedState>,
+    iss_gen: &tcp::IssGenerator,
+    checksums: tcp::ChecksumCapabilities,
+    fragments: &mut fragment::FragmentCache,
+    buf: &[u8],
+) -> io::Result<()> {
+    let Some(version) = buf.first() else {
+        return Ok(());
+    };
+    match version >> 4 {
+        4 => match parse_ipv4(buf, checksums, fragments) {
+            Ok(Some((ip, segment))) => dispatch(nic, shared, iss_gen, checksums, ip, &segment),
+            Ok(None) => Ok(()),
+            Err(err) => {
+                eprintln!("corrupted ipv4 packet: err={err:?}");
+                Ok(())
+            }
+        },
+        6 => match parse_ipv6(buf) {
+            Ok(Some((ip, segment))) => dispatch(nic, shared, iss_gen, checksums, ip, segment),
+            Ok(None) => Ok(()),
+            Err(err) => {
+                eprintln!("corrupted ipv6 packet: err={err:?}");
+                Ok(())
+            }
+        },
+        other => {
+            eprintln!("unsupported ip version: {other}");
+            Ok(())
+        }
+    }
+}
+
+fn dispatch(
+    nic: &mut tun_tap::Iface,
+    shared: &Arc<SharedState>,
+    iss_gen: &tcp::IssGenerator,
+    checksums: tcp::ChecksumCapabilities,
+    ip: IpMeta,
+    segment: &[u8],
+) -> io::Result<()> {
+    match etherparse::TcpHeaderSlice::from_slice(segment) {
+        Ok(tcp_hdr) => {
+            let tcp_hdr_size = tcp_hdr.slice().len();
+            let data = &segment[tcp_hdr_size..];
+            let quad = Quad {
+                src: (ip.src, tcp_hdr.source_port()),
+                dst: (ip.dst, tcp_hdr.destination_port()),
+            };
+            let port = tcp_hdr.destination_port();
+            let mut state = shared.state.lock().unwrap();
+            let listening = state.pending.contains_key(&port);
+            match state.connections.entry(quad) {
+                std::collections::hash_map::Entry::Occupied(mut c) => {
+                    c.get_mut().on_packet(nic, ip, tcp_hdr.clone(), data)?;
+                }
+                std::collections::hash_map::Entry::Vacant(v) => {
+                    if !listening {
+                        // Nobody is listening on this port; ignore the SYN.
+                        return Ok(());
+                    }
+                    if let Some(c) =
+                        tcp::Connection::accept(nic, ip, tcp_hdr.clone(), iss_gen, checksums)?
+                    {
+                        v.insert(c);
+                    }
+                }
+            };
+            if let Some(conn) = state.connections.get_mut(&quad) {
+                if conn.take_newly_established() {
+                    state.pending.entry(port).or_default().push_back(quad);
+                }
+            }
+            shared.cond.notify_all();
+        }
+        Err(err) => {
+            eprintln!("corrupted tcp frame: err={err:?}");
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,11 @@
+//! A minimal, from-scratch TCP/IP stack running over a tun device.
+//!
+//! [`Interface`] brings up the tun device and drives every connection's
+//! state machine in the background; [`TcpListener`]/[`TcpStream`] expose it
+//! as an ordinary blocking socket API.
+
+mod fragment;
+pub mod interface;
+pub mod tcp;
+
+pub use interface::{Interface, TcpListener, TcpStream};
@@ -1,73 +1,36 @@
-mod tcp;
-
-use std::{collections::HashMap, net::Ipv4Addr};
+use std::thread;
 
+use cft_tcp::Interface;
 use color_eyre::Result;
 
-#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
-struct Quad {
-    src: (Ipv4Addr, u16),
-    dst: (Ipv4Addr, u16),
-}
-
+/// Minimal echo server: accepts connections on port 9000 and writes back
+/// whatever it reads, demonstrating the blocking socket API in
+/// `cft_tcp::interface`.
 fn main() -> Result<()> {
     tracing_subscriber::fmt::init();
-    let mut nic = tun_tap::Iface::without_packet_info("tun0", tun_tap::Mode::Tun)?;
-    let mut tcp_conn: HashMap<Quad, tcp::Connection> = HashMap::new();
+    let iface = Interface::new("tun0")?;
+    let listener = iface.bind(9000)?;
     loop {
-        let mut buf = [0u8; 1500];
-        let read = nic.recv(&mut buf[..])?;
-        match etherparse::Ipv4HeaderSlice::from_slice(&buf[0..read]) {
-            Ok(ip_hdr) => {
-                if ip_hdr.protocol() != 0x6 {
-                    continue; // not tcp
-                }
-                let ipv4_hdr_size = ip_hdr.slice().len();
-                match etherparse::TcpHeaderSlice::from_slice(&buf[ipv4_hdr_size..read]) {
-                    Ok(tcp_hdr) => {
-                        let tcp_hdr_size = tcp_hdr.slice().len();
-                        let data = &buf[ipv4_hdr_size + tcp_hdr_size..read];
-                        let quad = Quad {
-                            src: (ip_hdr.source_addr(), tcp_hdr.source_port()),
-                            dst: (ip_hdr.source_addr(), tcp_hdr.destination_port()),
-                        };
-                        match tcp_conn.entry(quad) {
-                            std::collections::hash_map::Entry::Occupied(mut c) => {
-                                c.get_mut().on_packet(
-                                    &mut nic,
-                                    ip_hdr.clone(),
-                                    tcp_hdr.clone(),
-                                    data,
-                                )?;
-                            }
-                            std::collections::hash_map::Entry::Vacant(v) => {
-                                if let Some(c) = tcp::Connection::accept(
-                                    &mut nic,
-                                    ip_hdr.clone(),
-                                    tcp_hdr.clone(),
-                                )? {
-                                    v.insert(c);
-                                }
-                            }
-                        };
-                        // println!(
-                        //     "from {}:{} to {}:{}",
-                        //     ip_hdr.source_addr(),
-                        //     tcp_hdr.source_port(),
-                        //     ip_hdr.destination_addr(),
-                        //     tcp_hdr.destination_port()
-                        // );
+        let stream = listener.accept()?;
+        thread::spawn(move || {
+            let mut buf = [0u8; 1500];
+            loop {
+                match stream.read(&mut buf) {
+                    Ok(0) => {
+                        let _ = stream.close();
+                        return;
+                    }
+                    Ok(n) => {
+                        if stream.write(&buf[..n]).is_err() {
+                            return;
+                        }
                     }
                     Err(err) => {
-                        eprintln!("corrupted tcp frame: err={err:?}");
+                        eprintln!("read failed: err={err:?}");
+                        return;
                     }
                 }
             }
-            Err(err) => {
-                eprintln!("corrupted ipv4 packet: err={:?}", err);
-            }
-        }
+        });
     }
-
-    Ok(())
 }
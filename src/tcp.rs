@@ -1,9 +1,239 @@
-use etherparse::{Ipv4Header, Ipv4HeaderSlice, TcpHeader, TcpHeaderSlice};
-use std::io::{self, Cursor};
-use std::num::Wrapping;
+use etherparse::{Ipv4Header, Ipv6Header, TcpHeader, TcpHeaderSlice};
+use std::cmp::Ordering;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io::{self, Cursor, Write as _};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::ops::{Add, Sub};
+use std::time::{Duration, Instant};
 use tracing::debug;
 use tun_tap::Iface;
 
+/// An IP address, abstracting over v4/v6 so `Quad` and `Connection` don't
+/// need to be duplicated per address family.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+pub enum IpAddress {
+    V4(Ipv4Addr),
+    V6(Ipv6Addr),
+}
+
+/// The source and destination addresses of an inbound datagram - enough of
+/// its IP header for the TCP layer to build a reply, regardless of which IP
+/// version it arrived on.
+#[derive(Clone, Copy)]
+pub struct IpMeta {
+    pub src: IpAddress,
+    pub dst: IpAddress,
+}
+
+/// Whichever IP header etherparse produced for this connection's address
+/// family. Keeping `Connection` free of a type parameter in exchange for
+/// this small enum, which exposes only the handful of operations the TCP
+/// layer needs: setting the payload length, serializing, and computing the
+/// TCP pseudo-header checksum.
+enum IpHeader {
+    V4(Ipv4Header),
+    V6(Ipv6Header),
+}
+
+impl IpHeader {
+    fn new(local: IpAddress, remote: IpAddress, payload_len: u16) -> Self {
+        match (local, remote) {
+            (IpAddress::V4(local), IpAddress::V4(remote)) => IpHeader::V4(Ipv4Header::new(
+                payload_len,
+                64,
+                etherparse::IpNumber::Tcp,
+                local.octets(),
+                remote.octets(),
+            )),
+            (IpAddress::V6(local), IpAddress::V6(remote)) => IpHeader::V6(Ipv6Header {
+                payload_length: payload_len,
+                next_header: etherparse::IpNumber::Tcp,
+                hop_limit: 64,
+                source: local.octets(),
+                destination: remote.octets(),
+                ..Default::default()
+            }),
+            _ => unreachable!("local and remote endpoints must share an address family"),
+        }
+    }
+
+    fn set_payload_len(&mut self, len: usize) {
+        match self {
+            IpHeader::V4(ip) => ip.set_payload_len(len).expect("ip header too large"),
+            IpHeader::V6(ip) => ip.payload_length = len as u16,
+        }
+    }
+
+    fn write(&self, buf: &mut impl io::Write) -> Result<(), etherparse::WriteError> {
+        match self {
+            IpHeader::V4(ip) => ip.write(buf),
+            IpHeader::V6(ip) => ip.write(buf),
+        }
+    }
+
+    fn calc_tcp_checksum(&self, tcp: &TcpHeader, payload: &[u8]) -> u16 {
+        match self {
+            IpHeader::V4(ip) => tcp.calc_checksum_ipv4(ip, payload),
+            IpHeader::V6(ip) => tcp.calc_checksum_ipv6(ip, payload),
+        }
+        .expect("fail to calculate tcp checksum")
+    }
+
+    /// Recomputes the TCP checksum over `tcp_hdr`/`payload` using this
+    /// pseudo-header and compares it against the checksum the segment
+    /// actually carried.
+    fn verify_tcp_checksum(&self, tcp_hdr: &TcpHeaderSlice, payload: &[u8]) -> bool {
+        let expected = self.calc_tcp_checksum(&tcp_hdr.to_header(), payload);
+        expected == tcp_hdr.checksum()
+    }
+}
+
+/// Whether to verify (rx) or compute (tx) a checksum, or skip it because
+/// the tun device or NIC already handles it out-of-band (e.g. checksum
+/// offload).
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ChecksumAction {
+    #[default]
+    Validate,
+    Ignore,
+}
+
+/// Per-protocol, per-direction checksum handling for a `Connection`.
+/// Defaults to validating everything; set a field to `Ignore` to skip rx
+/// verification of that protocol's checksum, or to leave its tx checksum
+/// field as zero for an offload-capable NIC to fill in.
+///
+/// IPv6 has no header checksum, so `ipv4_rx`/`ipv4_tx` only affect IPv4
+/// traffic.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChecksumCapabilities {
+    pub ipv4_rx: ChecksumAction,
+    pub ipv4_tx: ChecksumAction,
+    pub tcp_rx: ChecksumAction,
+    pub tcp_tx: ChecksumAction,
+}
+
+impl ChecksumCapabilities {
+    /// Disables every checksum check/computation, for a tun device or NIC
+    /// that already guarantees integrity out-of-band.
+    pub fn ignored() -> Self {
+        ChecksumCapabilities {
+            ipv4_rx: ChecksumAction::Ignore,
+            ipv4_tx: ChecksumAction::Ignore,
+            tcp_rx: ChecksumAction::Ignore,
+            tcp_tx: ChecksumAction::Ignore,
+        }
+    }
+}
+
+/// Starting retransmission timeout, per RFC 6298 ("until a round-trip time
+/// (RTT) measurement has been made... the RTO SHOULD be set to 1 second").
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+/// Floor on the retransmission timeout, so a handful of back-to-back fast
+/// acks can't spin the RTO down to something that fires on jitter alone.
+const MIN_RTO: Duration = Duration::from_millis(200);
+/// Ceiling on the exponential backoff applied to a repeatedly-expiring RTO.
+const MAX_RTO: Duration = Duration::from_secs(60);
+/// How long a connection lingers in TIME-WAIT before it's considered closed.
+/// RFC 793 recommends 2*MSL with MSL = 2 minutes; that's used here verbatim.
+const TIME_WAIT_DURATION: Duration = Duration::from_secs(2 * 120);
+
+/// Generates initial sequence numbers per RFC 6528: `ISS = M + F(...)`,
+/// where `M` is a clock that ticks every 4 microseconds and `F` is a keyed
+/// hash of the connection's four-tuple. Keeping the key fixed for the
+/// process lifetime makes ISNs unpredictable to an off-path attacker while
+/// still increasing monotonically (via `M`) for any given tuple, so a stale
+/// segment from an earlier incarnation of the same tuple falls outside the
+/// new connection's window.
+pub struct IssGenerator {
+    secret: RandomState,
+    start: Instant,
+}
+
+impl IssGenerator {
+    pub fn new() -> Self {
+        IssGenerator {
+            secret: RandomState::new(),
+            start: Instant::now(),
+        }
+    }
+
+    fn generate(&self, local: (IpAddress, u16), remote: (IpAddress, u16)) -> SeqNumber {
+        let m = (self.start.elapsed().as_micros() / 4) as u32;
+        let mut hasher = self.secret.build_hasher();
+        local.hash(&mut hasher);
+        remote.hash(&mut hasher);
+        let f = hasher.finish() as u32;
+        SeqNumber::new(m.wrapping_add(f))
+    }
+}
+
+impl Default for IssGenerator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A point in the 32-bit TCP sequence number space.
+///
+/// Sequence numbers wrap around modulo 2^32, so raw `u32` comparisons break
+/// down near the wraparound boundary (e.g. `0xFFFFFFFF` should be considered
+/// "before" `0x00000001`). `SeqNumber` stores the value as `i32` and defines
+/// ordering by the sign of the wrapping difference between two numbers,
+/// which makes comparisons correct across overflow without any special
+/// casing at call sites.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+struct SeqNumber(i32);
+
+impl SeqNumber {
+    fn new(n: u32) -> Self {
+        SeqNumber(n as i32)
+    }
+
+    fn to_u32(self) -> u32 {
+        self.0 as u32
+    }
+}
+
+impl Add<usize> for SeqNumber {
+    type Output = SeqNumber;
+
+    fn add(self, rhs: usize) -> Self::Output {
+        let rhs = i32::try_from(rhs).expect("sequence advance does not fit in i32");
+        SeqNumber(self.0.wrapping_add(rhs))
+    }
+}
+
+impl Sub<usize> for SeqNumber {
+    type Output = SeqNumber;
+
+    fn sub(self, rhs: usize) -> Self::Output {
+        let rhs = i32::try_from(rhs).expect("sequence advance does not fit in i32");
+        SeqNumber(self.0.wrapping_sub(rhs))
+    }
+}
+
+impl Sub<SeqNumber> for SeqNumber {
+    type Output = usize;
+
+    // Distance from `rhs` to `self`, i.e. how far `self` has advanced past
+    // `rhs`. Panics if `self` is actually behind `rhs`, mirroring the
+    // unsigned-underflow panic callers would get from plain `u32` math.
+    fn sub(self, rhs: SeqNumber) -> Self::Output {
+        let diff = self.0.wrapping_sub(rhs.0);
+        assert!(diff >= 0, "SeqNumber subtraction underflowed");
+        diff as usize
+    }
+}
+
+impl PartialOrd for SeqNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.0.wrapping_sub(other.0).cmp(&0))
+    }
+}
+
 pub enum State {
     Closed,
     Listen,
@@ -31,9 +261,68 @@ pub struct Connection {
     state: State,
     tx: SendSequence,
     rx: ReceiveSequence,
-    ip: Ipv4Header,
+    ip: IpHeader,
     tcp: TcpHeader,
     buf: Cursor<[u8; 1500]>,
+    /// Bytes in `[SND.UNA, SND.NXT)`: sent but not yet acknowledged, kept
+    /// around so the RTO timer can retransmit them verbatim.
+    unacked: VecDeque<u8>,
+    /// Sequence number occupied by our FIN, once one has been sent. Used to
+    /// tell whether an incoming ack also acknowledges the FIN, and whether
+    /// a pending retransmission needs to carry the FIN flag.
+    fin_seq: Option<SeqNumber>,
+    /// Last byte we transmitted, kept so a zero-window probe still has
+    /// something to send once `unacked` has fully drained.
+    last_octet: Option<u8>,
+    timers: Timers,
+    /// Bytes received and acked but not yet consumed by `TcpStream::read`.
+    incoming: VecDeque<u8>,
+    /// Bytes queued by `TcpStream::write` that haven't been handed to
+    /// `send` yet, because they fall outside the current send window.
+    outgoing: VecDeque<u8>,
+    /// Set by `TcpStream`'s close handle; acted on by the next `on_tick`,
+    /// since only the poll loop holds the `Iface` needed to send the FIN.
+    close_requested: bool,
+    checksums: ChecksumCapabilities,
+    /// Set the first time `take_newly_established` reports this connection
+    /// as established, so the poll loop enqueues it for `TcpListener::accept`
+    /// exactly once.
+    accepted: bool,
+}
+
+/// Cap on how many bytes of `outgoing` a single call to `flush_outgoing`
+/// hands to `send`, so a large write doesn't get coalesced into a segment
+/// bigger than what comfortably fits in `Connection::buf`.
+const MAX_SEGMENT_SIZE: usize = 1024;
+
+struct Timers {
+    /// When the oldest currently-unacked segment was last (re)transmitted.
+    send_time: Option<Instant>,
+    /// When the last zero-window probe went out.
+    probe_time: Option<Instant>,
+    srtt: Option<Duration>,
+    rttvar: Duration,
+    rto: Duration,
+    /// Consecutive RTO expirations without a fresh ack; drives the
+    /// exponential backoff applied to `rto`.
+    backoff: u32,
+    /// Set on entering TIME-WAIT so `on_tick` can tell when the 2*MSL quiet
+    /// time has elapsed and the connection can be torn down.
+    time_wait_since: Option<Instant>,
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Timers {
+            send_time: None,
+            probe_time: None,
+            srtt: None,
+            rttvar: Duration::ZERO,
+            rto: INITIAL_RTO,
+            backoff: 0,
+            time_wait_since: None,
+        }
+    }
 }
 
 // Send Sequence Space
@@ -59,13 +348,13 @@ pub struct Connection {
 //               update
 //     ISS     - initial send sequence number
 struct SendSequence {
-    una: Wrapping<u32>,
-    nxt: Wrapping<u32>,
+    una: SeqNumber,
+    nxt: SeqNumber,
     wnd: u16,
     up: bool,
     wl1: usize,
     wl2: usize,
-    iss: u32,
+    iss: SeqNumber,
 }
 
 // Receive Sequence Space
@@ -87,37 +376,49 @@ struct SendSequence {
 //       IRS     - initial receive sequence number
 
 struct ReceiveSequence {
-    nxt: Wrapping<u32>,
+    nxt: SeqNumber,
     wnd: u16,
     up: bool,
-    irs: u32,
+    irs: SeqNumber,
 }
 
 impl Connection {
     pub fn accept(
         nic: &mut Iface,
-        ip_hdr: Ipv4HeaderSlice,
+        ip: IpMeta,
         tcp_hdr: TcpHeaderSlice,
+        iss_gen: &IssGenerator,
+        checksums: ChecksumCapabilities,
     ) -> io::Result<Option<Self>> {
         if !tcp_hdr.syn() {
             return Ok(None);
         }
 
-        let iss = 0;
+        if checksums.tcp_rx == ChecksumAction::Validate
+            && !IpHeader::new(ip.src, ip.dst, 0).verify_tcp_checksum(&tcp_hdr, &[])
+        {
+            debug!("dropping syn with invalid tcp checksum");
+            return Ok(None);
+        }
+
+        let iss = iss_gen.generate(
+            (ip.dst, tcp_hdr.destination_port()),
+            (ip.src, tcp_hdr.source_port()),
+        );
         let wnd = tcp_hdr.window_size();
-        let tcp = TcpHeader::new(tcp_hdr.destination_port(), tcp_hdr.source_port(), iss, wnd);
-        let ip = Ipv4Header::new(
-            tcp.header_len(),
-            64,
-            etherparse::IpNumber::Tcp,
-            ip_hdr.destination_addr().octets(),
-            ip_hdr.source_addr().octets(),
+        let tcp = TcpHeader::new(
+            tcp_hdr.destination_port(),
+            tcp_hdr.source_port(),
+            iss.to_u32(),
+            wnd,
         );
+        let ip = IpHeader::new(ip.dst, ip.src, tcp.header_len());
+        let irs = SeqNumber::new(tcp_hdr.sequence_number());
         let mut c = Connection {
             state: State::SynRcvd,
             tx: SendSequence {
-                una: Wrapping(iss),
-                nxt: Wrapping(iss + 1),
+                una: iss,
+                nxt: iss + 1,
                 wnd: tcp_hdr.window_size(),
                 up: false,
                 wl1: 0,
@@ -125,68 +426,381 @@ impl Connection {
                 iss,
             },
             rx: ReceiveSequence {
-                nxt: Wrapping(tcp_hdr.sequence_number()) + Wrapping(1),
+                nxt: irs + 1,
                 wnd: tcp_hdr.window_size(),
                 up: false,
-                irs: tcp_hdr.sequence_number(),
+                irs,
             },
             tcp,
             ip,
             buf: Cursor::new([0; 1500]),
+            unacked: VecDeque::new(),
+            fin_seq: None,
+            last_octet: None,
+            timers: Timers::default(),
+            incoming: VecDeque::new(),
+            outgoing: VecDeque::new(),
+            close_requested: false,
+            checksums,
+            accepted: false,
         };
 
-        let mut tcp = &mut c.tcp;
+        let tcp = &mut c.tcp;
         tcp.syn = true;
         tcp.ack = true;
         c.send(nic, &[])?;
         Ok(Some(c))
     }
 
-    fn send(&mut self, nic: &mut Iface, payload: &[u8]) -> io::Result<usize> {
-        let mut tcp = &mut self.tcp;
+    /// Writes a segment for the given sequence number directly to the wire,
+    /// bypassing `SND.NXT` bookkeeping. Used both for first-time sends (via
+    /// `send`, which advances `SND.NXT` afterwards) and for retransmissions
+    /// of already-sent bytes, which must reuse their original sequence
+    /// number.
+    fn transmit(&mut self, nic: &mut Iface, seq: SeqNumber, payload: &[u8]) -> io::Result<usize> {
+        let tcp = &mut self.tcp;
         let buf = &mut self.buf;
         buf.set_position(0);
-        tcp.sequence_number = self.tx.nxt.0;
-        tcp.acknowledgment_number = self.rx.nxt.0;
+        tcp.sequence_number = seq.to_u32();
+        tcp.acknowledgment_number = self.rx.nxt.to_u32();
         let ip = &mut self.ip;
-        ip.set_payload_len(tcp.header_len() as usize + payload.len())
-            .expect("ip header too large");
-        let checksum = tcp
-            .calc_checksum_ipv4(&ip, &[])
-            .expect("fail to calculate tcp checksum");
-        tcp.checksum = checksum;
+        ip.set_payload_len(tcp.header_len() as usize + payload.len());
+        tcp.checksum = match self.checksums.tcp_tx {
+            ChecksumAction::Validate => ip.calc_tcp_checksum(tcp, payload),
+            ChecksumAction::Ignore => 0,
+        };
         let written = {
             ip.write(buf).map_err(|err| match err {
                 etherparse::WriteError::IoError(err) => err,
-                _ => unimplemented!(),
+                err => io::Error::new(io::ErrorKind::InvalidData, format!("{err}")),
             })?;
             tcp.write(buf)?;
+            buf.write_all(payload)?;
             buf.position() as usize
         };
-        self.tx.nxt += payload.len() as u32;
-        if self.tcp.syn {
-            self.tx.nxt += 1;
-        }
-        if self.tcp.fin {
-            self.tx.nxt += 1;
+        // `Ipv4Header::write` always fills in a correct header checksum;
+        // honor an offload-capable NIC's preference for a zeroed field by
+        // clearing it back out at its fixed offset (bytes 10..12).
+        if matches!(ip, IpHeader::V4(_)) && self.checksums.ipv4_tx == ChecksumAction::Ignore {
+            buf.get_mut()[10..12].fill(0);
         }
         nic.send(&buf.get_ref()[..written])?;
+        Ok(written)
+    }
+
+    /// Sends a brand-new segment: `payload` plus whatever control flags are
+    /// currently set on `self.tcp`. Advances `SND.NXT` past the bytes sent,
+    /// and, for `SYN`/`FIN`, resets the one-shot flag once it has been
+    /// accounted for so it isn't re-applied to later segments.
+    fn send(&mut self, nic: &mut Iface, payload: &[u8]) -> io::Result<usize> {
+        let seq = self.tx.nxt;
+        let had_syn = self.tcp.syn;
+        let had_fin = self.tcp.fin;
+        self.transmit(nic, seq, payload)?;
+        self.unacked.extend(payload.iter().copied());
+        if let Some(&b) = payload.last() {
+            self.last_octet = Some(b);
+        }
+        self.tcp.rst = false;
+        self.tx.nxt = self.tx.nxt + payload.len();
+        if had_syn {
+            self.tx.nxt = self.tx.nxt + 1;
+            self.tcp.syn = false;
+        }
+        if had_fin {
+            self.fin_seq = Some(self.tx.nxt);
+            self.tx.nxt = self.tx.nxt + 1;
+            self.tcp.fin = false;
+        }
+        if self.has_outstanding_segment() && self.timers.send_time.is_none() {
+            self.timers.send_time = Some(Instant::now());
+        }
         Ok(payload.len())
     }
 
+    /// True while there's something the RTO timer should be watching for a
+    /// retransmission: unacked data bytes, or a sent FIN that hasn't been
+    /// acked yet. A bare FIN never occupies `unacked`, so it needs its own
+    /// check to avoid a lost FIN hanging the close forever.
+    fn has_outstanding_segment(&self) -> bool {
+        !self.unacked.is_empty() || (self.fin_seq.is_some() && !self.fin_acked())
+    }
+
     fn send_rst(&mut self, nic: &mut Iface) -> io::Result<()> {
         self.tcp.rst = true;
         self.send(nic, &[])?;
         Ok(())
     }
 
+    /// Begins an active close: sends our FIN and moves to the appropriate
+    /// half-closed state. A no-op outside `Established`/`CloseWait`.
+    pub fn close(&mut self, nic: &mut Iface) -> io::Result<()> {
+        match &self.state {
+            State::Established => {
+                self.tcp.fin = true;
+                self.send(nic, &[])?;
+                self.state = State::FinWait1;
+            }
+            State::CloseWait => {
+                self.tcp.fin = true;
+                self.send(nic, &[])?;
+                self.state = State::LastAck;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    pub fn is_closed(&self) -> bool {
+        matches!(self.state, State::Closed)
+    }
+
+    pub fn is_established(&self) -> bool {
+        matches!(self.state, State::Established)
+    }
+
+    /// True the first time this is called after the connection reaches
+    /// `Established`, and false on every call after that - used by the
+    /// poll loop to enqueue a freshly-accepted connection for
+    /// `TcpListener::accept` exactly once, rather than on every packet it
+    /// subsequently receives.
+    pub fn take_newly_established(&mut self) -> bool {
+        if self.is_established() && !self.accepted {
+            self.accepted = true;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Applies an incoming ack: advances `SND.UNA`/`SND.WND`, drops
+    /// newly-acknowledged bytes from the retransmission queue and feeds the
+    /// round-trip sample into the RTO estimator.
+    fn on_ack(&mut self, tcp_hdr: &TcpHeaderSlice) {
+        self.tx.wnd = tcp_hdr.window_size();
+        let ackn = SeqNumber::new(tcp_hdr.acknowledgment_number());
+        let advanced = ackn - self.tx.una;
+        for _ in 0..advanced {
+            self.unacked.pop_front();
+        }
+        self.tx.una = ackn;
+        if advanced == 0 {
+            return;
+        }
+        self.timers.backoff = 0;
+        self.timers.probe_time = None;
+        if let Some(send_time) = self.timers.send_time.take() {
+            self.update_rto_estimate(send_time.elapsed());
+        }
+        if self.has_outstanding_segment() {
+            self.timers.send_time = Some(Instant::now());
+        }
+    }
+
+    /// Applies an incoming ack and, if the segment carries data, appends it
+    /// to `self.incoming` (for `TcpStream::read` to drain), advances
+    /// `RCV.NXT` and acks it back - piggybacking the ack on a queued
+    /// outgoing write when there is one, rather than sending a bare ack.
+    fn on_incoming_data(
+        &mut self,
+        nic: &mut Iface,
+        tcp_hdr: &TcpHeaderSlice,
+        data: &[u8],
+    ) -> io::Result<()> {
+        self.on_ack(tcp_hdr);
+        if !data.is_empty() {
+            // `check_valid_segment` only guarantees the segment falls inside
+            // the receive window, not that it starts exactly where we left
+            // off. A segment beginning past `RCV.NXT` is a gap (out-of-order
+            // delivery, or a retransmission we're still waiting behind) -
+            // reassembly isn't implemented, so drop it and re-ack `RCV.NXT`
+            // to prompt the peer to resend from the right place, rather than
+            // committing it at the wrong offset.
+            if SeqNumber::new(tcp_hdr.sequence_number()) == self.rx.nxt {
+                self.incoming.extend(data.iter().copied());
+                self.rx.nxt = self.rx.nxt + data.len();
+                if !self.flush_outgoing(nic)? {
+                    self.send(nic, &[])?;
+                }
+            } else {
+                self.send(nic, &[])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends as much of `self.outgoing` as the current send window and
+    /// `MAX_SEGMENT_SIZE` allow. Returns whether anything was sent, so
+    /// callers that need to ack can skip an otherwise-redundant empty
+    /// segment.
+    fn flush_outgoing(&mut self, nic: &mut Iface) -> io::Result<bool> {
+        let in_flight = self.unacked.len();
+        let window = (self.tx.wnd as usize).saturating_sub(in_flight);
+        let n = self.outgoing.len().min(window).min(MAX_SEGMENT_SIZE);
+        if n == 0 {
+            return Ok(false);
+        }
+        let payload: Vec<u8> = self.outgoing.drain(..n).collect();
+        self.send(nic, &payload)?;
+        Ok(true)
+    }
+
+    /// Queues bytes written via `TcpStream::write` for the next
+    /// `flush_outgoing`, called from the poll loop that owns the `Iface`.
+    pub fn queue_outgoing(&mut self, data: &[u8]) {
+        self.outgoing.extend(data.iter().copied());
+    }
+
+    /// Drains and returns everything received so far, for `TcpStream::read`.
+    pub fn take_incoming(&mut self) -> Vec<u8> {
+        self.incoming.drain(..).collect()
+    }
+
+    pub fn has_incoming(&self) -> bool {
+        !self.incoming.is_empty()
+    }
+
+    /// True once the peer has sent a FIN, so a blocked reader with an empty
+    /// `incoming` buffer knows to stop waiting instead of hanging forever.
+    pub fn peer_closed(&self) -> bool {
+        matches!(
+            self.state,
+            State::CloseWait | State::Closing | State::LastAck | State::TimeWait | State::Closed
+        )
+    }
+
+    /// Marks this connection for an active close on the next tick, since
+    /// `TcpStream::close` doesn't itself hold the `Iface` needed to send
+    /// the FIN.
+    pub fn request_close(&mut self) {
+        self.close_requested = true;
+    }
+
+    /// True once an ack has covered the sequence number our FIN occupied.
+    fn fin_acked(&self) -> bool {
+        match self.fin_seq {
+            Some(fin_seq) => self.tx.una >= fin_seq + 1,
+            None => false,
+        }
+    }
+
+    /// RFC 6298 RTO estimation: update the smoothed RTT and its variance
+    /// from a fresh round-trip sample, then derive the RTO from them.
+    fn update_rto_estimate(&mut self, sample: Duration) {
+        let (srtt, rttvar) = match self.timers.srtt {
+            None => (sample, sample / 2),
+            Some(srtt) => {
+                let delta = if sample > srtt {
+                    sample - srtt
+                } else {
+                    srtt - sample
+                };
+                let rttvar = self.timers.rttvar.mul_f64(0.75) + delta.mul_f64(0.25);
+                let srtt = srtt.mul_f64(0.875) + sample.mul_f64(0.125);
+                (srtt, rttvar)
+            }
+        };
+        self.timers.srtt = Some(srtt);
+        self.timers.rttvar = rttvar;
+        self.timers.rto = (srtt + rttvar * 4).clamp(MIN_RTO, MAX_RTO);
+    }
+
+    /// Doubles the RTO (capped at `MAX_RTO`) after a timeout with no ack,
+    /// per the exponential backoff rule in RFC 6298 §5.5.
+    fn on_retransmit_timeout(&mut self) {
+        self.timers.backoff = self.timers.backoff.saturating_add(1);
+        self.timers.rto = (self.timers.rto * 2).min(MAX_RTO);
+    }
+
+    /// Resends the oldest unacked bytes (at most `MAX_SEGMENT_SIZE` of them)
+    /// at their original sequence number, carrying the FIN flag if the FIN
+    /// directly follows the bytes resent.
+    fn retransmit(&mut self, nic: &mut Iface) -> io::Result<()> {
+        let payload: Vec<u8> = self
+            .unacked
+            .iter()
+            .copied()
+            .take(MAX_SEGMENT_SIZE)
+            .collect();
+        let had_fin = self.tcp.fin;
+        // The FIN occupies the sequence number right after the last unacked
+        // data byte, so it only rides along when the whole region fits.
+        self.tcp.fin = self.fin_seq == Some(self.tx.una + payload.len());
+        self.transmit(nic, self.tx.una, &payload)?;
+        self.tcp.fin = had_fin;
+        Ok(())
+    }
+
+    /// Sends a single already-transmitted byte to elicit a window update
+    /// from a peer advertising `SND.WND == 0`, per RFC 1122 §4.2.2.17.
+    fn send_zero_window_probe(&mut self, nic: &mut Iface) -> io::Result<()> {
+        let probe = match (self.unacked.front().copied(), self.last_octet) {
+            (Some(b), _) => Some((self.tx.una, b)),
+            (None, Some(b)) => Some((self.tx.nxt - 1, b)),
+            (None, None) => None,
+        };
+        if let Some((seq, byte)) = probe {
+            self.transmit(nic, seq, &[byte])?;
+        }
+        Ok(())
+    }
+
+    /// Driven from the poll loop in `interface.rs` on every tick: flushes any
+    /// queued outgoing writes, honors a pending `TcpStream::close`,
+    /// retransmits the oldest unacked segment once the RTO has expired,
+    /// probes a zero-window peer, and expires a connection that has sat in
+    /// TIME-WAIT for 2*MSL.
+    pub fn on_tick(&mut self, nic: &mut Iface) -> io::Result<()> {
+        if matches!(self.state, State::TimeWait) {
+            if let Some(since) = self.timers.time_wait_since {
+                if since.elapsed() >= TIME_WAIT_DURATION {
+                    self.state = State::Closed;
+                }
+            }
+            return Ok(());
+        }
+        self.flush_outgoing(nic)?;
+        if self.close_requested && self.outgoing.is_empty() {
+            self.close(nic)?;
+            self.close_requested = false;
+        }
+        if self.tx.wnd == 0 {
+            let due = match self.timers.probe_time {
+                Some(t) => t.elapsed() >= self.timers.rto,
+                None => true,
+            };
+            if due {
+                self.send_zero_window_probe(nic)?;
+                self.timers.probe_time = Some(Instant::now());
+                self.on_retransmit_timeout();
+            }
+            return Ok(());
+        }
+        if self.has_outstanding_segment() {
+            if let Some(send_time) = self.timers.send_time {
+                if send_time.elapsed() >= self.timers.rto {
+                    self.retransmit(nic)?;
+                    self.timers.send_time = Some(Instant::now());
+                    self.on_retransmit_timeout();
+                }
+            }
+        }
+        Ok(())
+    }
+
     pub fn on_packet(
         &mut self,
         nic: &mut Iface,
-        ip_hdr: Ipv4HeaderSlice,
+        _ip: IpMeta,
         tcp_hdr: TcpHeaderSlice,
         data: &[u8],
     ) -> io::Result<usize> {
+        if self.checksums.tcp_rx == ChecksumAction::Validate
+            && !self.ip.verify_tcp_checksum(&tcp_hdr, data)
+        {
+            debug!("dropping segment with invalid tcp checksum");
+            return Ok(0);
+        }
         if let Err(err) = self.check_acceptable_ack(tcp_hdr.acknowledgment_number()) {
             debug!(error=?err, "invalid acknowledgment number");
             if !self.state.is_sync() {
@@ -195,10 +809,11 @@ impl Connection {
             return Ok(0);
         }
         if let Err(err) = self.check_valid_segment(&tcp_hdr, data.len()) {
-            debug!(error=?err, "invalid segment");
-            if !self.state.is_sync() {
-                self.send_rst(nic)?;
-            }
+            // An out-of-window segment (e.g. a peer retransmission of data
+            // we already acked) is dropped and re-acked, never reset, per
+            // RFC 793's segment-arrival processing.
+            debug!(error=?err, "dropping out-of-window segment");
+            self.send(nic, &[])?;
             return Ok(0);
         }
         match &self.state {
@@ -211,17 +826,72 @@ impl Connection {
                         "must get an ack",
                     ));
                 }
+                self.on_ack(&tcp_hdr);
                 self.state = State::Established;
                 Ok(0)
             }
-            State::SynSent => todo!(),
-            State::Established => todo!(),
-            State::FinWait1 => todo!(),
-            State::FinWait2 => todo!(),
-            State::Closing => todo!(),
-            State::TimeWait => todo!(),
-            State::CloseWait => todo!(),
-            State::LastAck => todo!(),
+            // Active opens aren't implemented; `Connection`s are only ever
+            // created by `accept`, which skips straight to `SynRcvd`. Should
+            // this state ever be reached, fail safe rather than panic.
+            State::SynSent => Ok(0),
+            State::Established => {
+                self.on_incoming_data(nic, &tcp_hdr, data)?;
+                if tcp_hdr.fin() {
+                    self.rx.nxt = self.rx.nxt + 1;
+                    self.state = State::CloseWait;
+                    self.send(nic, &[])?;
+                }
+                Ok(data.len())
+            }
+            State::FinWait1 => {
+                self.on_incoming_data(nic, &tcp_hdr, data)?;
+                match (self.fin_acked(), tcp_hdr.fin()) {
+                    (true, true) => {
+                        self.rx.nxt = self.rx.nxt + 1;
+                        self.timers.time_wait_since = Some(Instant::now());
+                        self.state = State::TimeWait;
+                        self.send(nic, &[])?;
+                    }
+                    (true, false) => self.state = State::FinWait2,
+                    (false, true) => {
+                        self.rx.nxt = self.rx.nxt + 1;
+                        self.state = State::Closing;
+                        self.send(nic, &[])?;
+                    }
+                    (false, false) => {}
+                }
+                Ok(0)
+            }
+            State::FinWait2 => {
+                self.on_incoming_data(nic, &tcp_hdr, data)?;
+                if tcp_hdr.fin() {
+                    self.rx.nxt = self.rx.nxt + 1;
+                    self.timers.time_wait_since = Some(Instant::now());
+                    self.state = State::TimeWait;
+                    self.send(nic, &[])?;
+                }
+                Ok(0)
+            }
+            State::Closing => {
+                self.on_ack(&tcp_hdr);
+                if self.fin_acked() {
+                    self.timers.time_wait_since = Some(Instant::now());
+                    self.state = State::TimeWait;
+                }
+                Ok(0)
+            }
+            State::CloseWait => {
+                self.on_ack(&tcp_hdr);
+                Ok(0)
+            }
+            State::LastAck => {
+                self.on_ack(&tcp_hdr);
+                if self.fin_acked() {
+                    self.state = State::Closed;
+                }
+                Ok(0)
+            }
+            State::TimeWait => Ok(0),
         }
     }
 
@@ -231,8 +901,15 @@ impl Connection {
     //   the inequality below holds:
 
     //     SND.UNA < SEG.ACK =< SND.NXT
+    //
+    // Relaxed to `SND.UNA =< SEG.ACK =< SND.NXT`: a peer sending data
+    // without having anything new to acknowledge (the common case right
+    // after the handshake) legitimately acks exactly `SND.UNA`, and that
+    // must be accepted as a non-advancing (duplicate) ack rather than
+    // rejected - `on_ack` already treats a zero advance as a no-op.
     fn check_acceptable_ack(&self, ackn: u32) -> io::Result<()> {
-        if is_between(self.tx.una + Wrapping(1), self.tx.nxt, ackn) {
+        let ackn = SeqNumber::new(ackn);
+        if self.tx.una <= ackn && ackn <= self.tx.nxt {
             Ok(())
         } else {
             Err(io::Error::new(
@@ -257,9 +934,9 @@ impl Connection {
     //   >0      >0     RCV.NXT =< SEG.SEQ < RCV.NXT+RCV.WND
     //               or RCV.NXT =< SEG.SEQ+SEG.LEN-1 < RCV.NXT+RCV.WND
     fn check_valid_segment(&self, tcp_hdr: &TcpHeaderSlice, slen: usize) -> io::Result<()> {
-        let seqn = tcp_hdr.sequence_number();
+        let seqn = SeqNumber::new(tcp_hdr.sequence_number());
         if slen == 0 && tcp_hdr.window_size() == 0 {
-            if seqn != self.rx.nxt.0 {
+            if seqn != self.rx.nxt {
                 return Err(io::Error::new(
                     io::ErrorKind::InvalidInput,
                     "seg.seq should equals rcv.nxt if  seg.len = 0 and seg.wnd = 0",
@@ -272,8 +949,8 @@ impl Connection {
                 "seg.wnd should not be 0 if seg.len > 0",
             ));
         }
-        let end = self.rx.nxt + Wrapping(self.rx.wnd as u32);
-        if is_between(self.rx.nxt, end - Wrapping(1), seqn) {
+        let end = self.rx.nxt + self.rx.wnd as usize;
+        if self.rx.nxt <= seqn && seqn < end {
             Ok(())
         } else {
             Err(io::Error::new(
@@ -284,11 +961,50 @@ impl Connection {
     }
 }
 
-fn is_between(start: Wrapping<u32>, end: Wrapping<u32>, val: u32) -> bool {
-    let val = Wrapping(val);
-    if start < end {
-        val >= start && val <= end
-    } else {
-        val >= start || val <= end
+#[cfg(test)]
+mod tests {
+    use super::SeqNumber;
+
+    #[test]
+    fn equal_seq_numbers_compare_equal() {
+        assert_eq!(SeqNumber::new(42), SeqNumber::new(42));
+        assert!(SeqNumber::new(42) <= SeqNumber::new(42));
+    }
+
+    #[test]
+    fn ordering_wraps_around_u32_boundary() {
+        // 0xFFFFFFFF is the sequence number just before wraparound, so it
+        // must be considered "before" 0x00000001, not after it as plain u32
+        // comparison would say.
+        assert!(SeqNumber::new(0xFFFFFFFF) < SeqNumber::new(0x00000001));
+        assert!(SeqNumber::new(0x00000001) > SeqNumber::new(0xFFFFFFFF));
+    }
+
+    #[test]
+    fn ordering_holds_within_half_the_space() {
+        assert!(SeqNumber::new(100) < SeqNumber::new(200));
+        assert!(SeqNumber::new(200) > SeqNumber::new(100));
+    }
+
+    #[test]
+    fn add_and_sub_usize_wrap_and_round_trip() {
+        let near_wrap = SeqNumber::new(0xFFFFFFFE);
+        assert_eq!((near_wrap + 4).to_u32(), 2);
+        assert_eq!(((near_wrap + 4) - 4).to_u32(), near_wrap.to_u32());
+    }
+
+    #[test]
+    fn sub_seq_number_gives_forward_distance_across_wraparound() {
+        let before = SeqNumber::new(0xFFFFFFFE);
+        let after = before + 4;
+        assert_eq!(after - before, 4);
+    }
+
+    #[test]
+    #[should_panic(expected = "SeqNumber subtraction underflowed")]
+    fn sub_seq_number_panics_when_rhs_is_ahead() {
+        let behind = SeqNumber::new(10);
+        let ahead = SeqNumber::new(20);
+        let _ = behind - ahead;
     }
 }